@@ -0,0 +1,297 @@
+//       ___           ___           ___           ___
+//      /\__\         /\  \         /\  \         /\__\
+//     /:/  /         \:\  \        \:\  \       /::|  |
+//    /:/__/           \:\  \        \:\  \     /:|:|  |
+//   /::\  \ ___       /::\  \       /::\  \   /:/|:|__|__
+//  /:/\:\  /\__\     /:/\:\__\     /:/\:\__\ /:/ |::::\__\
+//  \/__\:\/:/  /    /:/  \/__/    /:/  \/__/ \/__/~~/:/  /
+//       \::/  /    /:/  /        /:/  /            /:/  /
+//       /:/  /     \/__/         \/__/            /:/  /
+//      /:/  /                                    /:/  /
+//      \/__/                                     \/__/
+//
+// (c) Robert Swinford <robert.swinford<...at...>gmail.com>
+//
+// For the full copyright and license information, please view the LICENSE file
+// that was distributed with this source code.
+
+// a small on-disk "docket", in the spirit of Mercurial's dirstate docket: a cache
+// file recording what we already know about the mount table and per-dataset
+// snapshot listings, each tagged with a validity token so we can tell whether
+// a re-scan is necessary without doing the expensive scan itself
+
+use fxhash::FxHashMap as HashMap;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Default)]
+pub struct HttmCache {
+    mount_table: Option<CachedMountTable>,
+    snapshot_listings: HashMap<PathBuf, CachedSnapshotListing>,
+}
+
+struct CachedMountTable {
+    // mtime of /proc/mounts at the time we recorded this
+    source_mtime: SystemTime,
+    mount_collection: Vec<(String, String)>,
+}
+
+struct CachedSnapshotListing {
+    // mtime of the .zfs/snapshot directory itself at the time we recorded this
+    dir_mtime: SystemTime,
+    snapshot_names: Vec<OsString>,
+}
+
+impl HttmCache {
+    // returns the cached mount collection only if the recorded mtime still matches
+    pub fn mount_collection_if_fresh(
+        &self,
+        current_source_mtime: SystemTime,
+    ) -> Option<&Vec<(String, String)>> {
+        self.mount_table.as_ref().and_then(|cached| {
+            if cached.source_mtime == current_source_mtime {
+                Some(&cached.mount_collection)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn set_mount_collection(
+        &mut self,
+        source_mtime: SystemTime,
+        mount_collection: Vec<(String, String)>,
+    ) {
+        self.mount_table = Some(CachedMountTable {
+            source_mtime,
+            mount_collection,
+        });
+    }
+
+    // returns the cached snapshot subdirectory names only if the .zfs/snapshot
+    // directory's mtime still matches what we recorded - an absent entry or a
+    // stale mtime both mean "go re-read_dir it"
+    pub fn snapshot_names_if_fresh(
+        &self,
+        hidden_snapshot_dir: &Path,
+        current_dir_mtime: SystemTime,
+    ) -> Option<&Vec<OsString>> {
+        self.snapshot_listings
+            .get(hidden_snapshot_dir)
+            .and_then(|cached| {
+                if cached.dir_mtime == current_dir_mtime {
+                    Some(&cached.snapshot_names)
+                } else {
+                    None
+                }
+            })
+    }
+
+    pub fn set_snapshot_names(
+        &mut self,
+        hidden_snapshot_dir: PathBuf,
+        dir_mtime: SystemTime,
+        snapshot_names: Vec<OsString>,
+    ) {
+        self.snapshot_listings.insert(
+            hidden_snapshot_dir,
+            CachedSnapshotListing {
+                dir_mtime,
+                snapshot_names,
+            },
+        );
+    }
+}
+
+pub fn cache_file_path() -> PathBuf {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+
+    cache_home.join("httm").join("cache")
+}
+
+// corruption-tolerant: any failure to read or parse the cache file is treated
+// as an empty cache, never an error - a stale/garbled cache should cost us a
+// re-scan, not a crash
+pub fn load() -> HttmCache {
+    let path = cache_file_path();
+
+    let raw = match std::fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(_) => return HttmCache::default(),
+    };
+
+    parse(&raw).unwrap_or_default()
+}
+
+pub fn save(cache: &HttmCache) -> io::Result<()> {
+    let path = cache_file_path();
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(serialize(cache).as_bytes())
+}
+
+// one entry per line, fields separated by '\x1f' (unit separator), so that
+// path components containing common delimiters like ':' or ',' are safe
+fn serialize(cache: &HttmCache) -> String {
+    let mut out = String::new();
+
+    if let Some(mount_table) = &cache.mount_table {
+        out.push_str("mounts");
+        out.push('\x1f');
+        out.push_str(&encode_mtime(mount_table.source_mtime));
+        mount_table.mount_collection.iter().for_each(|(fs, mount)| {
+            out.push('\x1f');
+            out.push_str(fs);
+            out.push('\x1e');
+            out.push_str(mount);
+        });
+        out.push('\n');
+    }
+
+    cache
+        .snapshot_listings
+        .iter()
+        .for_each(|(hidden_snapshot_dir, listing)| {
+            out.push_str("snaps");
+            out.push('\x1f');
+            out.push_str(&hidden_snapshot_dir.to_string_lossy());
+            out.push('\x1f');
+            out.push_str(&encode_mtime(listing.dir_mtime));
+            listing.snapshot_names.iter().for_each(|name| {
+                out.push('\x1f');
+                out.push_str(&name.to_string_lossy());
+            });
+            out.push('\n');
+        });
+
+    out
+}
+
+fn parse(raw: &str) -> Option<HttmCache> {
+    let mut cache = HttmCache::default();
+
+    for line in raw.lines() {
+        let mut fields = line.split('\x1f');
+
+        match fields.next()? {
+            "mounts" => {
+                let source_mtime = decode_mtime(fields.next()?)?;
+                let mount_collection = fields
+                    .map(|pair| {
+                        let mut parts = pair.splitn(2, '\x1e');
+                        let fs = parts.next()?.to_owned();
+                        let mount = parts.next()?.to_owned();
+                        Some((fs, mount))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                cache.mount_table = Some(CachedMountTable {
+                    source_mtime,
+                    mount_collection,
+                });
+            }
+            "snaps" => {
+                let hidden_snapshot_dir = PathBuf::from(fields.next()?);
+                let dir_mtime = decode_mtime(fields.next()?)?;
+                let snapshot_names = fields.map(OsString::from).collect();
+
+                cache.snapshot_listings.insert(
+                    hidden_snapshot_dir,
+                    CachedSnapshotListing {
+                        dir_mtime,
+                        snapshot_names,
+                    },
+                );
+            }
+            _ => return None,
+        }
+    }
+
+    Some(cache)
+}
+
+// encoded as "secs.nanos" - truncating to whole seconds would make every
+// cache entry a guaranteed miss on ext4/xfs/zfs, which all routinely report
+// non-zero mtime nanoseconds, defeating the entire point of a persistent cache
+fn encode_mtime(time: SystemTime) -> String {
+    let duration = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!("{}.{}", duration.as_secs(), duration.subsec_nanos())
+}
+
+fn decode_mtime(encoded: &str) -> Option<SystemTime> {
+    let mut parts = encoded.splitn(2, '.');
+    let secs: u64 = parts.next()?.parse().ok()?;
+    let nanos: u32 = parts.next()?.parse().ok()?;
+
+    Some(SystemTime::UNIX_EPOCH + std::time::Duration::new(secs, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_snapshot_listing_mtime_at_nanosecond_precision() {
+        let mut cache = HttmCache::default();
+        // a mtime with a nonzero sub-second component, as real filesystems report
+        let dir_mtime =
+            SystemTime::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 123_456_789);
+
+        cache.set_snapshot_names(
+            PathBuf::from("/tank/.zfs/snapshot"),
+            dir_mtime,
+            vec![OsString::from("snap1"), OsString::from("snap2")],
+        );
+
+        let reloaded = parse(&serialize(&cache)).expect("serialized cache should parse back");
+
+        assert_eq!(
+            reloaded.snapshot_names_if_fresh(Path::new("/tank/.zfs/snapshot"), dir_mtime),
+            Some(&vec![OsString::from("snap1"), OsString::from("snap2")])
+        );
+    }
+
+    #[test]
+    fn round_trips_mount_table_mtime_at_nanosecond_precision() {
+        let mut cache = HttmCache::default();
+        let source_mtime =
+            SystemTime::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 987_654_321);
+        let mount_collection = vec![("zfs".to_owned(), "/tank".to_owned())];
+
+        cache.set_mount_collection(source_mtime, mount_collection.clone());
+
+        let reloaded = parse(&serialize(&cache)).expect("serialized cache should parse back");
+
+        assert_eq!(
+            reloaded.mount_collection_if_fresh(source_mtime),
+            Some(&mount_collection)
+        );
+    }
+
+    #[test]
+    fn stale_mtime_is_a_cache_miss() {
+        let mut cache = HttmCache::default();
+        let dir_mtime = SystemTime::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 1);
+
+        cache.set_snapshot_names(PathBuf::from("/tank/.zfs/snapshot"), dir_mtime, vec![]);
+
+        let reloaded = parse(&serialize(&cache)).expect("serialized cache should parse back");
+        let changed_mtime = dir_mtime + std::time::Duration::from_nanos(1);
+
+        assert!(reloaded
+            .snapshot_names_if_fresh(Path::new("/tank/.zfs/snapshot"), changed_mtime)
+            .is_none());
+    }
+}
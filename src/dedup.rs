@@ -0,0 +1,209 @@
+//       ___           ___           ___           ___
+//      /\__\         /\  \         /\  \         /\__\
+//     /:/  /         \:\  \        \:\  \       /::|  |
+//    /:/__/           \:\  \        \:\  \     /:|:|  |
+//   /::\  \ ___       /::\  \       /::\  \   /:/|:|__|__
+//  /:/\:\  /\__\     /:/\:\__\     /:/\:\__\ /:/ |::::\__\
+//  \/__\:\/:/  /    /:/  \/__/    /:/  \/__/ \/__/~~/:/  /
+//       \::/  /    /:/  /        /:/  /            /:/  /
+//       /:/  /     \/__/         \/__/            /:/  /
+//      /:/  /                                    /:/  /
+//      \/__/                                     \/__/
+//
+// (c) Robert Swinford <robert.swinford<...at...>gmail.com>
+//
+// For the full copyright and license information, please view the LICENSE file
+// that was distributed with this source code.
+
+use crate::PathData;
+
+use fxhash::FxHashMap as HashMap;
+use rayon::prelude::*;
+use std::time::SystemTime;
+
+// how should we decide two versions of a file are "the same" and collapse them
+// into a single entry?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupBy {
+    // the original, cheap behavior: same modify time and size means same version
+    MtimeAndSize,
+    // hash file contents and collapse on matching digest, falling back to
+    // mtime/size for anything we can't open (e.g. a phantom)
+    Content,
+}
+
+impl Default for DedupBy {
+    fn default() -> Self {
+        DedupBy::MtimeAndSize
+    }
+}
+
+// collapse a list of versions down to one PathData per distinct version, keeping
+// the earliest system_time for any versions which are considered duplicates
+pub fn dedup_pathdata(dedup_by: DedupBy, pathdata_set: Vec<PathData>) -> Vec<PathData> {
+    match dedup_by {
+        DedupBy::MtimeAndSize => dedup_by_mtime_and_size(pathdata_set),
+        DedupBy::Content => dedup_by_content(pathdata_set),
+    }
+}
+
+fn dedup_by_mtime_and_size(pathdata_set: Vec<PathData>) -> Vec<PathData> {
+    let mut unique_versions: HashMap<(SystemTime, u64), PathData> = HashMap::default();
+
+    pathdata_set.into_iter().for_each(|pathdata| {
+        // keep the earliest version for any (mtime, size) collision
+        unique_versions
+            .entry((pathdata.system_time, pathdata.size))
+            .and_modify(|existing| {
+                if pathdata.system_time < existing.system_time {
+                    *existing = pathdata.clone();
+                }
+            })
+            .or_insert(pathdata);
+    });
+
+    unique_versions.into_iter().map(|(_, v)| v).collect()
+}
+
+fn dedup_by_content(pathdata_set: Vec<PathData>) -> Vec<PathData> {
+    // first pass: bucket candidates by size, as files of different sizes can
+    // never share a content hash, so there's no reason to pay for hashing them
+    let mut size_buckets: HashMap<u64, Vec<PathData>> = HashMap::default();
+
+    pathdata_set.into_iter().for_each(|pathdata| {
+        size_buckets
+            .entry(pathdata.size)
+            .or_default()
+            .push(pathdata);
+    });
+
+    // second pass: within each size-collision bucket, stream file contents
+    // through a fast hasher in parallel, and key the dedup map on the digest
+    let hashed: Vec<(HashKey, PathData)> = size_buckets
+        .into_par_iter()
+        .flat_map(|(size, bucket)| {
+            if bucket.len() == 1 {
+                // no collision on size, so no need to hash at all
+                bucket
+                    .into_iter()
+                    .map(|pathdata| (HashKey::MtimeAndSize(pathdata.system_time, size), pathdata))
+                    .collect::<Vec<_>>()
+            } else {
+                bucket
+                    .into_par_iter()
+                    .map(|pathdata| {
+                        let key = match hash_file(&pathdata) {
+                            Some(digest) => HashKey::Digest(digest),
+                            // fall back gracefully to the cheap key when a file
+                            // can't be opened (e.g. a phantom)
+                            None => HashKey::MtimeAndSize(pathdata.system_time, size),
+                        };
+                        (key, pathdata)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        })
+        .collect();
+
+    let mut unique_versions: HashMap<HashKey, PathData> = HashMap::default();
+
+    hashed.into_iter().for_each(|(key, pathdata)| {
+        // versions sharing a digest collapse to the earliest system_time;
+        // versions with differing digests are preserved as distinct, even if
+        // they happen to share mtime/size
+        unique_versions
+            .entry(key)
+            .and_modify(|existing| {
+                if pathdata.system_time < existing.system_time {
+                    *existing = pathdata.clone();
+                }
+            })
+            .or_insert(pathdata);
+    });
+
+    unique_versions.into_iter().map(|(_, v)| v).collect()
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum HashKey {
+    Digest([u8; 32]),
+    MtimeAndSize(SystemTime, u64),
+}
+
+// shared with the --verify integrity report, which hashes the same way to
+// compare live content against snapshot versions
+pub(crate) fn hash_file(pathdata: &PathData) -> Option<[u8; 32]> {
+    if pathdata.is_phantom {
+        return None;
+    }
+
+    let mut file = std::fs::File::open(&pathdata.path_buf).ok()?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        match std::io::Read::read(&mut file, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[..n]);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+
+    Some(*hasher.finalize().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // a unique path per test run/case, so parallel test threads don't collide
+    fn temp_file(case: &str, contents: &[u8]) -> std::path::PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "httm-dedup-test-{}-{}-{case}",
+            std::process::id(),
+            blake3::hash(case.as_bytes()).to_hex()
+        ));
+
+        let mut file = std::fs::File::create(&path).expect("create temp file");
+        file.write_all(contents).expect("write temp file");
+
+        path
+    }
+
+    #[test]
+    fn dedup_by_content_collapses_identical_content_of_the_same_size() {
+        let path_a = temp_file("identical-a", b"same bytes, same size");
+        let path_b = temp_file("identical-b", b"same bytes, same size");
+
+        let deduped = dedup_by_content(vec![
+            PathData::from(path_a.as_path()),
+            PathData::from(path_b.as_path()),
+        ]);
+
+        assert_eq!(deduped.len(), 1);
+
+        let _ = std::fs::remove_file(path_a);
+        let _ = std::fs::remove_file(path_b);
+    }
+
+    #[test]
+    fn dedup_by_content_keeps_distinct_content_of_the_same_size() {
+        let path_a = temp_file("distinct-a", b"aaaaaaaaaaaaaaaaaaaaaa");
+        let path_b = temp_file("distinct-b", b"bbbbbbbbbbbbbbbbbbbbbb");
+
+        let deduped = dedup_by_content(vec![
+            PathData::from(path_a.as_path()),
+            PathData::from(path_b.as_path()),
+        ]);
+
+        assert_eq!(deduped.len(), 2);
+
+        let _ = std::fs::remove_file(path_a);
+        let _ = std::fs::remove_file(path_b);
+    }
+}
@@ -15,6 +15,8 @@
 // For the full copyright and license information, please view the LICENSE file
 // that was distributed with this source code.
 
+use crate::dedup::{dedup_pathdata, DedupBy};
+use crate::fs_type::{run_with_concurrency, RemoteConcurrency};
 use crate::library::enumerate_directory;
 use crate::lookup::get_search_dirs;
 use crate::{Config, PathData};
@@ -60,13 +62,31 @@ pub fn get_deleted(
     path: &Path,
 ) -> Result<Vec<PathData>, Box<dyn std::error::Error + Send + Sync + 'static>> {
     let immediate_dataset_deleted = vec![path]
-        .into_iter().flat_map(|path| get_search_dirs(config, &PathData::from(path), false)).flat_map(|search_dirs| get_deleted_per_dataset(path, search_dirs))
+        .into_iter()
+        .flat_map(|path| get_search_dirs(config, &PathData::from(path), false, None))
+        .flat_map(|search_dirs| {
+            get_deleted_per_dataset(
+                path,
+                search_dirs,
+                config.opt_dedup_by,
+                config.opt_remote_concurrency,
+            )
+        })
         .flatten()
         .collect();
 
     let combined_deleted: Vec<PathData> = if config.opt_alt_replicated {
         let alt_replicated_deleted = vec![path]
-            .into_iter().flat_map(|path| get_search_dirs(config, &PathData::from(path), true)).flat_map(|search_dirs| get_deleted_per_dataset(path, search_dirs))
+            .into_iter()
+            .flat_map(|path| get_search_dirs(config, &PathData::from(path), true, None))
+            .flat_map(|search_dirs| {
+                get_deleted_per_dataset(
+                    path,
+                    search_dirs,
+                    config.opt_dedup_by,
+                    config.opt_remote_concurrency,
+                )
+            })
             .flatten()
             .collect();
 
@@ -103,16 +123,23 @@ pub fn get_deleted(
 fn get_deleted_per_dataset(
     path: &Path,
     search_dirs: (PathBuf, PathBuf),
+    dedup_by: DedupBy,
+    remote_concurrency: Option<usize>,
 ) -> Result<Vec<PathData>, Box<dyn std::error::Error + Send + Sync + 'static>> {
     let (hidden_snapshot_dir, local_path) = search_dirs;
 
+    // an explicit --remote-concurrency wins; otherwise auto-detect off the
+    // dataset's mount so network-backed (NFS/SMB) datasets fall back to a
+    // bounded fan-out instead of an unbounded par_bridge
+    let concurrency = remote_concurrency
+        .map(RemoteConcurrency::Capped)
+        .unwrap_or_else(|| RemoteConcurrency::auto_detect(&hidden_snapshot_dir));
+
     // get all local entries we need to compare against these to know
     // what is a deleted file
-    let local_dir_entries: Vec<DirEntry> = std::fs::read_dir(&path)?
-        .into_iter()
-        .par_bridge()
-        .flatten()
-        .collect();
+    let local_dir_entries: Vec<DirEntry> = run_with_concurrency(concurrency, || {
+        std::fs::read_dir(path).map(|read_dir| read_dir.par_bridge().flatten().collect())
+    })??;
 
     // create a collection of local unique file names
     let mut local_unique_filenames: HashMap<OsString, DirEntry> = HashMap::default();
@@ -121,17 +148,21 @@ fn get_deleted_per_dataset(
     });
 
     // now create a collection of file names in the snap_dirs
-    let snap_files: Vec<(OsString, DirEntry)> = std::fs::read_dir(&hidden_snapshot_dir)?
-        .flatten()
-        .par_bridge()
-        .map(|entry| entry.path())
-        .map(|path| path.join(&local_path))
-        .map(|path| std::fs::read_dir(&path))
-        .flatten_iter()
-        .flatten_iter()
-        .flatten_iter()
-        .map(|dir_entry| (dir_entry.file_name(), dir_entry))
-        .collect();
+    let snap_files: Vec<(OsString, DirEntry)> = run_with_concurrency(concurrency, || {
+        std::fs::read_dir(&hidden_snapshot_dir).map(|read_dir| {
+            read_dir
+                .flatten()
+                .par_bridge()
+                .map(|entry| entry.path())
+                .map(|path| path.join(&local_path))
+                .map(|path| std::fs::read_dir(&path))
+                .flatten_iter()
+                .flatten_iter()
+                .flatten_iter()
+                .map(|dir_entry| (dir_entry.file_name(), dir_entry))
+                .collect()
+        })
+    })??;
 
     // create a list of unique filenames on snaps
     let mut unique_snap_filenames: HashMap<OsString, DirEntry> = HashMap::default();
@@ -145,16 +176,8 @@ fn get_deleted_per_dataset(
         .filter(|(file_name, _)| local_unique_filenames.get(file_name).is_none())
         .map(|(_, dir_entry)| PathData::from(&dir_entry));
 
-    // deduplicate all by modify time and size - as we would elsewhere
-    let mut unique_deleted_versions: HashMap<(SystemTime, u64), PathData> = HashMap::default();
-    deleted_pathdata.for_each(|pathdata| {
-        let _ = unique_deleted_versions.insert((pathdata.system_time, pathdata.size), pathdata);
-    });
-
-    let mut sorted: Vec<_> = unique_deleted_versions
-        .into_iter()
-        .map(|(_, v)| v)
-        .collect();
+    // deduplicate per the requested --dedup mode - as we would elsewhere
+    let mut sorted: Vec<PathData> = dedup_pathdata(dedup_by, deleted_pathdata.collect());
 
     sorted.par_sort_unstable_by_key(|pathdata| pathdata.system_time);
 
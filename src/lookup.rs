@@ -15,32 +15,50 @@
 // For the full copyright and license information, please view the LICENSE file
 // that was distributed with this source code.
 
+use crate::cache::{self, HttmCache};
+use crate::dedup::{dedup_pathdata, DedupBy};
+use crate::fs_type::{run_with_concurrency, RemoteConcurrency};
 use crate::{Config, HttmError, PathData, SnapPoint};
 use fxhash::FxHashMap as HashMap;
 use rayon::prelude::*;
-use std::{
-    path::{Path, PathBuf},
-    time::SystemTime,
-};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 pub fn lookup_exec(
     config: &Config,
     path_data: &Vec<PathData>,
 ) -> Result<[Vec<PathData>; 2], Box<dyn std::error::Error + Send + Sync + 'static>> {
+    // the docket: a cache of the mount table and per-dataset snapshot listings,
+    // loaded once per invocation and flushed back to disk when we're done -
+    // --no-cache skips it entirely
+    let snapshot_cache: Option<Mutex<HttmCache>> = if config.opt_no_cache {
+        None
+    } else {
+        Some(Mutex::new(cache::load()))
+    };
+
     let all_snaps: Vec<PathData> = if config.opt_alt_replicated {
         // create vec of all local and replicated backups
         path_data
             .into_par_iter()
             .map(|path_data| {
                 [
-                    get_search_dirs(config, path_data, true),
-                    get_search_dirs(config, path_data, false),
+                    get_search_dirs(config, path_data, true, snapshot_cache.as_ref()),
+                    get_search_dirs(config, path_data, false, snapshot_cache.as_ref()),
                 ]
             })
             .flatten()
             .map(|search_dirs| search_dirs.ok())
             .flatten()
-            .map(get_versions)
+            .map(|search_dirs| {
+                get_versions(
+                    search_dirs,
+                    config.opt_dedup_by,
+                    snapshot_cache.as_ref(),
+                    config.opt_remote_concurrency,
+                )
+            })
             .flatten()
             .flatten()
             .collect()
@@ -48,9 +66,16 @@ pub fn lookup_exec(
         // create vec of most local dataset/user specified backups
         path_data
             .into_par_iter()
-            .map(|path_data| get_search_dirs(config, path_data, false))
+            .map(|path_data| get_search_dirs(config, path_data, false, snapshot_cache.as_ref()))
             .flatten()
-            .map(get_versions)
+            .map(|search_dirs| {
+                get_versions(
+                    search_dirs,
+                    config.opt_dedup_by,
+                    snapshot_cache.as_ref(),
+                    config.opt_remote_concurrency,
+                )
+            })
             .flatten_iter()
             .flatten_iter()
             .collect::<Vec<PathData>>()
@@ -63,6 +88,13 @@ pub fn lookup_exec(
         Vec::new()
     };
 
+    if let Some(snapshot_cache) = &snapshot_cache {
+        if let Ok(guard) = snapshot_cache.lock() {
+            // best-effort: a failed cache write just means we re-scan next time
+            let _ = cache::save(&guard);
+        }
+    }
+
     // check if all files (snap and live) do not exist, if this is true, then user probably messed up
     // and entered a file that never existed (that is, perhaps a wrong file name)?
     if all_snaps.is_empty() && live_versions.iter().all(|i| i.is_phantom) {
@@ -79,6 +111,7 @@ pub fn get_search_dirs(
     config: &Config,
     file_pathdata: &PathData,
     for_alt_replicated: bool,
+    snapshot_cache: Option<&Mutex<HttmCache>>,
 ) -> Result<(PathBuf, PathBuf), Box<dyn std::error::Error + Send + Sync + 'static>> {
     // which ZFS dataset do we want to use
     let file_path = &file_pathdata.path_buf;
@@ -90,7 +123,7 @@ pub fn get_search_dirs(
         ),
         SnapPoint::Native(mount_collection) => {
             let immediate_dataset_snap_mount =
-                get_immediate_dataset(file_pathdata, mount_collection)?;
+                get_immediate_dataset(file_pathdata, mount_collection, snapshot_cache)?;
 
             if for_alt_replicated {
                 get_alt_replicated_dataset(&immediate_dataset_snap_mount, mount_collection)?
@@ -174,38 +207,104 @@ fn get_alt_replicated_dataset(
 
 fn get_versions(
     search_dirs: (PathBuf, PathBuf),
+    dedup_by: DedupBy,
+    snapshot_cache: Option<&Mutex<HttmCache>>,
+    remote_concurrency: Option<usize>,
+) -> Result<Vec<PathData>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let versions = get_raw_versions(search_dirs, snapshot_cache, remote_concurrency)?;
+
+    // dedup_pathdata collapses versions per the requested --dedup mode - this
+    // is also where we'd lose snapshot identity, so anything that needs to
+    // know *which* snapshots held a version (see unique_versions) must call
+    // get_raw_versions directly instead
+    let mut sorted: Vec<PathData> = dedup_pathdata(dedup_by, versions);
+
+    sorted.par_sort_unstable_by_key(|pathdata| pathdata.system_time);
+
+    Ok(sorted)
+}
+
+// every version of local_path found across all snapshots under
+// hidden_snapshot_dir, phantoms filtered out, before any deduplication - the
+// snapshot name each version came from is still embedded as a path component
+pub(crate) fn get_raw_versions(
+    search_dirs: (PathBuf, PathBuf),
+    snapshot_cache: Option<&Mutex<HttmCache>>,
+    remote_concurrency: Option<usize>,
 ) -> Result<Vec<PathData>, Box<dyn std::error::Error + Send + Sync + 'static>> {
     let (hidden_snapshot_dir, local_path) = search_dirs;
 
-    // get the DirEntry for our snapshot path which will have all our possible
-    // needed snapshots, like so: .zfs/snapshots/<some snap name>/, some snap name
-    // are our entries here
-    let versions = std::fs::read_dir(hidden_snapshot_dir)?
-        .flatten()
-        .par_bridge()
-        .map(|entry| entry.path())
-        .map(|path| path.join(&local_path))
-        .map(|path| PathData::from(path.as_path()))
-        .filter(|pathdata| !pathdata.is_phantom)
-        .collect::<Vec<PathData>>();
-
-    // filter above will remove all the phantom values silently as we build a list of unique versions
-    // and our hashmap will then remove duplicates with the same system modify time and size/file len
-    let mut unique_versions: HashMap<(SystemTime, u64), PathData> = HashMap::default();
-    versions.into_iter().for_each(|pathdata| {
-        let _ = unique_versions.insert((pathdata.system_time, pathdata.size), pathdata);
+    // the names of the snapshots themselves, like so: .zfs/snapshot/<some snap name>/,
+    // some snap name are our entries here - on a cache hit we skip read_dir-ing
+    // hidden_snapshot_dir entirely and only stat the specific local_path join below
+    let snapshot_names = snapshot_names(&hidden_snapshot_dir, snapshot_cache)?;
+
+    // an explicit --remote-concurrency wins; otherwise auto-detect off the
+    // dataset's mount so a network-backed (NFS/SMB) dataset stats each
+    // snapshot's local_path join with a bounded fan-out instead of unbounded
+    let concurrency = remote_concurrency
+        .map(RemoteConcurrency::Capped)
+        .unwrap_or_else(|| RemoteConcurrency::auto_detect(&hidden_snapshot_dir));
+
+    run_with_concurrency(concurrency, || {
+        snapshot_names
+            .into_par_iter()
+            .map(|snapshot_name| hidden_snapshot_dir.join(snapshot_name).join(&local_path))
+            .map(|path| PathData::from(path.as_path()))
+            .filter(|pathdata| !pathdata.is_phantom)
+            .collect::<Vec<PathData>>()
+    })
+    .map_err(|err| err.into())
+}
+
+// consult the cache for the list of snapshot subdirectory names under
+// hidden_snapshot_dir, only re-read_dir-ing it when the entry is absent or its
+// recorded mtime is stale; falls back to a plain read_dir when caching is off
+fn snapshot_names(
+    hidden_snapshot_dir: &Path,
+    snapshot_cache: Option<&Mutex<HttmCache>>,
+) -> Result<Vec<std::ffi::OsString>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let snapshot_cache = match snapshot_cache {
+        Some(snapshot_cache) => snapshot_cache,
+        None => return Ok(read_dir_names(hidden_snapshot_dir)?),
+    };
+
+    let dir_mtime = std::fs::metadata(hidden_snapshot_dir)?.modified()?;
+
+    let cached = snapshot_cache.lock().ok().and_then(|guard| {
+        guard
+            .snapshot_names_if_fresh(hidden_snapshot_dir, dir_mtime)
+            .cloned()
     });
 
-    let mut sorted: Vec<PathData> = unique_versions.into_iter().map(|(_, v)| v).collect();
+    if let Some(cached) = cached {
+        return Ok(cached);
+    }
+
+    let fresh_names = read_dir_names(hidden_snapshot_dir)?;
 
-    sorted.par_sort_unstable_by_key(|pathdata| pathdata.system_time);
+    if let Ok(mut guard) = snapshot_cache.lock() {
+        guard.set_snapshot_names(
+            hidden_snapshot_dir.to_path_buf(),
+            dir_mtime,
+            fresh_names.clone(),
+        );
+    }
 
-    Ok(sorted)
+    Ok(fresh_names)
+}
+
+fn read_dir_names(hidden_snapshot_dir: &Path) -> std::io::Result<Vec<std::ffi::OsString>> {
+    Ok(std::fs::read_dir(hidden_snapshot_dir)?
+        .flatten()
+        .map(|entry| entry.file_name())
+        .collect())
 }
 
 pub fn get_immediate_dataset(
     pathdata: &PathData,
     mount_collection: &Vec<(String, String)>,
+    snapshot_cache: Option<&Mutex<HttmCache>>,
 ) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync + 'static>> {
     let file_path = &pathdata.path_buf;
 
@@ -213,8 +312,24 @@ pub fn get_immediate_dataset(
     // of previous work in the Pathdata new method, safe to unwrap or else here
     let parent_folder = file_path.parent().unwrap_or_else(|| Path::new("/"));
 
+    let source_mtime = mount_source_mtime();
+
+    // a fresh cache entry means /proc/mounts hasn't changed since we recorded
+    // it last run - search that recorded table instead of the one Config
+    // built this invocation; Config still derives its own table on every
+    // process start, the cache only decides which of the two is searched here
+    let cached_mount_collection =
+        source_mtime
+            .zip(snapshot_cache)
+            .and_then(|(source_mtime, snapshot_cache)| {
+                let guard = snapshot_cache.lock().ok()?;
+                guard.mount_collection_if_fresh(source_mtime).cloned()
+            });
+
+    let active_mount_collection = cached_mount_collection.as_ref().unwrap_or(mount_collection);
+
     // prune away most datasets by filtering - parent folder of file must contain relevant dataset
-    let potential_mountpoints: Vec<&String> = mount_collection
+    let potential_mountpoints: Vec<&String> = active_mount_collection
         .into_par_iter()
         .map(|(_, mount)| mount)
         .filter(|line| parent_folder.starts_with(line))
@@ -239,5 +354,24 @@ pub fn get_immediate_dataset(
             return Err(HttmError::new(&msg).into());
         };
 
-    Ok(PathBuf::from(best_potential_mountpoint))
+    let best_potential_mountpoint = PathBuf::from(best_potential_mountpoint);
+
+    // cache miss: record what we just searched, keyed to the current
+    // /proc/mounts mtime, so the next invocation can reuse it instead - with
+    // caching off (or no /proc/mounts mtime) nothing is recorded
+    if cached_mount_collection.is_none() {
+        if let (Some(source_mtime), Some(snapshot_cache)) = (source_mtime, snapshot_cache) {
+            if let Ok(mut guard) = snapshot_cache.lock() {
+                guard.set_mount_collection(source_mtime, mount_collection.clone());
+            }
+        }
+    }
+
+    Ok(best_potential_mountpoint)
+}
+
+// best-effort signal for "has /proc/mounts changed since we last cached it" - None (e.g. /proc/mounts is unreadable,
+// such as on a non-Linux host) just means we skip the mount-table cache
+fn mount_source_mtime() -> Option<SystemTime> {
+    std::fs::metadata("/proc/mounts").ok()?.modified().ok()
 }
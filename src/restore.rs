@@ -0,0 +1,169 @@
+//       ___           ___           ___           ___
+//      /\__\         /\  \         /\  \         /\__\
+//     /:/  /         \:\  \        \:\  \       /::|  |
+//    /:/__/           \:\  \        \:\  \     /:|:|  |
+//   /::\  \ ___       /::\  \       /::\  \   /:/|:|__|__
+//  /:/\:\  /\__\     /:/\:\__\     /:/\:\__\ /:/ |::::\__\
+//  \/__\:\/:/  /    /:/  \/__/    /:/  \/__/ \/__/~~/:/  /
+//       \::/  /    /:/  /        /:/  /            /:/  /
+//       /:/  /     \/__/         \/__/            /:/  /
+//      /:/  /                                    /:/  /
+//      \/__/                                     \/__/
+//
+// (c) Robert Swinford <robert.swinford<...at...>gmail.com>
+//
+// For the full copyright and license information, please view the LICENSE file
+// that was distributed with this source code.
+
+use crate::{Config, HttmError, PathData};
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// everything we need to know to perform a restore, before we touch disk - lets
+// --dry-run print exactly what would happen without side effects
+pub struct RestorePlan {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    // Some if a live file already sits at target and needs to be moved aside first
+    pub existing_backup: Option<PathBuf>,
+}
+
+// recover a chosen snapshot version back to its live location, or just print
+// the plan when config.opt_dry_run is set.
+//
+// live_path is the original file path the version listing was built from -
+// that is, the exact path the caller passed to lookup_exec/the interactive
+// skim view, not something we re-derive from snapshot_pathdata's path. We
+// can't safely reconstruct it by parsing the snapshot path: for
+// SnapPoint::UserDefined the local path was stripped against local_dir, not
+// snap_dir, and for --alt-replicated results the local path was stripped
+// against the *original* immediate dataset mount, not the alt-replicated
+// mount the version was actually found under. The caller always already
+// knows the live path it looked up, so we take it rather than guess.
+pub fn restore_exec(
+    config: &Config,
+    live_path: &Path,
+    snapshot_pathdata: &PathData,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let plan = plan_restore(live_path, snapshot_pathdata)?;
+
+    if config.opt_dry_run {
+        println!("{:?} -> {:?}", plan.source, plan.target);
+        if let Some(existing_backup) = &plan.existing_backup {
+            println!(
+                "(existing file at destination would be moved aside to {:?})",
+                existing_backup
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(existing_backup) = &plan.existing_backup {
+        std::fs::rename(&plan.target, existing_backup)?;
+    }
+
+    copy_preserving_metadata(&plan.source, &plan.target)
+}
+
+fn plan_restore(
+    live_path: &Path,
+    snapshot_pathdata: &PathData,
+) -> Result<RestorePlan, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let target = live_path.to_path_buf();
+
+    let existing_backup = if target.exists() {
+        Some(timestamped_backup_path(&target)?)
+    } else {
+        // nothing live to move aside - this is a deleted file, so we can just
+        // write straight to target
+        None
+    };
+
+    Ok(RestorePlan {
+        source: snapshot_pathdata.path_buf.clone(),
+        target,
+        existing_backup,
+    })
+}
+
+fn timestamped_backup_path(
+    target: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| HttmError::new("Restore target has no file name"))?;
+
+    let epoch_seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let backup_name = format!("{}.httm-{}", file_name.to_string_lossy(), epoch_seconds);
+
+    Ok(target.with_file_name(backup_name))
+}
+
+fn copy_preserving_metadata(
+    source: &Path,
+    target: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::copy(source, target)?;
+
+    let metadata = std::fs::metadata(source)?;
+    std::fs::set_permissions(target, metadata.permissions())?;
+    filetime::set_file_mtime(
+        target,
+        filetime::FileTime::from_last_modification_time(&metadata),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(case: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("httm-restore-test-{}-{case}", std::process::id()));
+        path
+    }
+
+    #[test]
+    fn plan_restore_moves_an_existing_live_file_aside_first() {
+        let live_path = temp_path("existing-live");
+        std::fs::write(&live_path, b"still here").expect("write live file");
+
+        let snapshot_pathdata = PathData::from(live_path.as_path());
+
+        let plan = plan_restore(&live_path, &snapshot_pathdata).expect("plan_restore");
+
+        assert_eq!(plan.target, live_path);
+        assert!(plan.existing_backup.is_some());
+        assert!(plan
+            .existing_backup
+            .as_ref()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(&*live_path.to_string_lossy()));
+
+        let _ = std::fs::remove_file(&live_path);
+    }
+
+    #[test]
+    fn plan_restore_has_no_backup_when_nothing_is_live() {
+        let live_path = temp_path("deleted-live");
+        let _ = std::fs::remove_file(&live_path);
+
+        // stand in for a deleted file's snapshot version - the source file
+        // itself doesn't need to exist for plan_restore, only the target check does
+        let snapshot_pathdata = PathData::from(live_path.as_path());
+
+        let plan = plan_restore(&live_path, &snapshot_pathdata).expect("plan_restore");
+
+        assert_eq!(plan.target, live_path);
+        assert!(plan.existing_backup.is_none());
+    }
+}
@@ -0,0 +1,191 @@
+//       ___           ___           ___           ___
+//      /\__\         /\  \         /\  \         /\__\
+//     /:/  /         \:\  \        \:\  \       /::|  |
+//    /:/__/           \:\  \        \:\  \     /:|:|  |
+//   /::\  \ ___       /::\  \       /::\  \   /:/|:|__|__
+//  /:/\:\  /\__\     /:/\:\__\     /:/\:\__\ /:/ |::::\__\
+//  \/__\:\/:/  /    /:/  \/__/    /:/  \/__/ \/__/~~/:/  /
+//       \::/  /    /:/  /        /:/  /            /:/  /
+//       /:/  /     \/__/         \/__/            /:/  /
+//      /:/  /                                    /:/  /
+//      \/__/                                     \/__/
+//
+// (c) Robert Swinford <robert.swinford<...at...>gmail.com>
+//
+// For the full copyright and license information, please view the LICENSE file
+// that was distributed with this source code.
+
+// a garbage-collection style analysis: for one or more paths, which snapshots
+// are the sole holder of some version of that path, and so cannot be
+// `zfs destroy`-ed without losing recoverable history?
+
+use crate::dedup::{hash_file, DedupBy};
+use crate::lookup::{get_raw_versions, get_search_dirs};
+use crate::{Config, PathData};
+
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// a single distinct version of a file, and every snapshot name that holds a
+// byte-for-byte (per dedup_by) copy of it
+pub struct SnapshotHolders {
+    pub version: PathData,
+    pub snapshot_names: Vec<String>,
+}
+
+pub fn unique_version_report(
+    config: &Config,
+    path: &Path,
+) -> Result<Vec<SnapshotHolders>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let pathdata = PathData::from(path);
+
+    // same datasets get_deleted consults: the immediate dataset, plus the
+    // alt-replicated one when enabled - otherwise a pool with alt-replicated
+    // mounts would have its "sole holder" classification silently ignore any
+    // snapshot only the replicated side still holds
+    let mut search_dir_list = vec![get_search_dirs(config, &pathdata, false, None)?];
+
+    if config.opt_alt_replicated {
+        search_dir_list.push(get_search_dirs(config, &pathdata, true, None)?);
+    }
+
+    // each version is tagged with the hidden_snapshot_dir it came from, since
+    // that's what snapshot_name needs to strip to recover the snapshot name -
+    // and the immediate and alt-replicated datasets have different ones
+    let raw_versions: Vec<(PathBuf, PathData)> = search_dir_list
+        .into_iter()
+        .map(|search_dirs| {
+            let hidden_snapshot_dir = search_dirs.0.clone();
+            get_raw_versions(search_dirs, None, config.opt_remote_concurrency).map(|versions| {
+                versions
+                    .into_iter()
+                    .map(|version| (hidden_snapshot_dir.clone(), version))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let mut groups: HashMap<VersionKey, Vec<(PathBuf, PathData)>> = HashMap::default();
+    raw_versions
+        .into_iter()
+        .for_each(|(hidden_snapshot_dir, pathdata)| {
+            let key = version_key(config.opt_dedup_by, &pathdata);
+            groups
+                .entry(key)
+                .or_default()
+                .push((hidden_snapshot_dir, pathdata));
+        });
+
+    Ok(groups
+        .into_iter()
+        .filter_map(|(_, mut versions)| {
+            let snapshot_names: Vec<String> = versions
+                .iter()
+                .filter_map(|(hidden_snapshot_dir, version)| {
+                    snapshot_name(hidden_snapshot_dir, &version.path_buf)
+                })
+                .collect();
+
+            versions.sort_unstable_by_key(|(_, version)| version.system_time);
+
+            versions
+                .into_iter()
+                .next()
+                .map(|(_, version)| SnapshotHolders {
+                    version,
+                    snapshot_names,
+                })
+        })
+        .collect())
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum VersionKey {
+    MtimeAndSize(SystemTime, u64),
+    Digest([u8; 32]),
+}
+
+fn version_key(dedup_by: DedupBy, pathdata: &PathData) -> VersionKey {
+    match dedup_by {
+        DedupBy::MtimeAndSize => VersionKey::MtimeAndSize(pathdata.system_time, pathdata.size),
+        DedupBy::Content => match hash_file(pathdata) {
+            Some(digest) => VersionKey::Digest(digest),
+            None => VersionKey::MtimeAndSize(pathdata.system_time, pathdata.size),
+        },
+    }
+}
+
+// the snapshot name is the path component directly under hidden_snapshot_dir,
+// e.g. hidden_snapshot_dir/<snap_name>/<local_path...>
+fn snapshot_name(hidden_snapshot_dir: &Path, version_path: &Path) -> Option<String> {
+    version_path
+        .strip_prefix(hidden_snapshot_dir)
+        .ok()?
+        .components()
+        .next()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+}
+
+// snapshots which hold at least one version found nowhere else, mapped to the
+// paths for which they're the sole holder - everything else is redundant
+pub struct SnapshotClassification {
+    pub load_bearing: HashMap<String, Vec<PathBuf>>,
+    pub redundant: Vec<String>,
+}
+
+pub fn classify_snapshots(
+    config: &Config,
+    paths: &[PathBuf],
+) -> Result<SnapshotClassification, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut load_bearing: HashMap<String, Vec<PathBuf>> = HashMap::default();
+    let mut all_snapshot_names: HashSet<String> = HashSet::default();
+
+    for path in paths {
+        let holders = unique_version_report(config, path)?;
+
+        holders.iter().for_each(|holder| {
+            holder.snapshot_names.iter().for_each(|snapshot_name| {
+                all_snapshot_names.insert(snapshot_name.clone());
+            });
+
+            if let [sole_holder] = holder.snapshot_names.as_slice() {
+                load_bearing
+                    .entry(sole_holder.clone())
+                    .or_default()
+                    .push(path.clone());
+            }
+        });
+    }
+
+    let redundant = all_snapshot_names
+        .into_iter()
+        .filter(|snapshot_name| !load_bearing.contains_key(snapshot_name))
+        .collect();
+
+    Ok(SnapshotClassification {
+        load_bearing,
+        redundant,
+    })
+}
+
+pub fn print_report(classification: &SnapshotClassification) {
+    classification
+        .load_bearing
+        .iter()
+        .for_each(|(snapshot_name, paths)| {
+            let paths_list = paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            println!(
+                "{} is the sole holder of versions of {}",
+                snapshot_name, paths_list
+            );
+        });
+}
@@ -0,0 +1,283 @@
+//       ___           ___           ___           ___
+//      /\__\         /\  \         /\  \         /\__\
+//     /:/  /         \:\  \        \:\  \       /::|  |
+//    /:/__/           \:\  \        \:\  \     /:|:|  |
+//   /::\  \ ___       /::\  \       /::\  \   /:/|:|__|__
+//  /:/\:\  /\__\     /:/\:\__\     /:/\:\__\ /:/ |::::\__\
+//  \/__\:\/:/  /    /:/  \/__/    /:/  \/__/ \/__/~~/:/  /
+//       \::/  /    /:/  /        /:/  /            /:/  /
+//       /:/  /     \/__/         \/__/            /:/  /
+//      /:/  /                                    /:/  /
+//      \/__/                                     \/__/
+//
+// (c) Robert Swinford <robert.swinford<...at...>gmail.com>
+//
+// For the full copyright and license information, please view the LICENSE file
+// that was distributed with this source code.
+
+// --verify/--checksum: compare a live file's content against every unique
+// snapshot version by checksum, and flag the hallmarks of silent corruption -
+// a version whose size/mtime match the live file but whose digest doesn't, or
+// a live file whose digest no longer matches any snapshot at all
+
+use crate::dedup::hash_file;
+use crate::PathData;
+
+use fxhash::FxHashMap as HashMap;
+use rayon::prelude::*;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub struct ChecksumTimeline {
+    pub digest_hex: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub snapshot_count: usize,
+}
+
+pub struct IntegrityReport {
+    pub live_path: PathBuf,
+    // None when the live file is missing/phantom
+    pub live_digest_hex: Option<String>,
+    pub live_matches_a_snapshot: bool,
+    pub timeline: Vec<ChecksumTimeline>,
+    // versions that share size and mtime with the live file, but whose content
+    // digest differs - the hallmark of bit rot
+    pub suspected_bit_rot: Vec<PathData>,
+}
+
+pub fn build_report(live: &PathData, snapshot_versions: &[PathData]) -> IntegrityReport {
+    let live_digest = hash_file(live);
+
+    // group candidate versions by size first, since different sizes can never
+    // share a digest, then hash each size-collision group in parallel
+    let digests: Vec<(PathBuf, SystemTime, [u8; 32])> = hash_all(snapshot_versions);
+
+    let mut by_digest: HashMap<[u8; 32], Vec<SystemTime>> = HashMap::default();
+    digests.iter().for_each(|(_, system_time, digest)| {
+        by_digest.entry(*digest).or_default().push(*system_time);
+    });
+
+    let mut timeline: Vec<ChecksumTimeline> = by_digest
+        .into_iter()
+        .map(|(digest, mut system_times)| {
+            system_times.sort_unstable();
+            ChecksumTimeline {
+                digest_hex: to_hex(&digest),
+                first_seen: *system_times.first().unwrap(),
+                last_seen: *system_times.last().unwrap(),
+                snapshot_count: system_times.len(),
+            }
+        })
+        .collect();
+
+    timeline.sort_unstable_by_key(|entry| entry.first_seen);
+
+    let live_matches_a_snapshot = match &live_digest {
+        Some(live_digest) => digests.iter().any(|(_, _, digest)| digest == live_digest),
+        None => false,
+    };
+
+    let suspected_bit_rot = snapshot_versions
+        .iter()
+        .filter(|version| version.system_time == live.system_time && version.size == live.size)
+        .filter(|version| match (hash_file(version), live_digest) {
+            (Some(version_digest), Some(live_digest)) => version_digest != live_digest,
+            _ => false,
+        })
+        .cloned()
+        .collect();
+
+    IntegrityReport {
+        live_path: live.path_buf.clone(),
+        live_digest_hex: live_digest.map(|digest| to_hex(&digest)),
+        live_matches_a_snapshot,
+        timeline,
+        suspected_bit_rot,
+    }
+}
+
+fn hash_all(pathdata_set: &[PathData]) -> Vec<(PathBuf, SystemTime, [u8; 32])> {
+    let mut size_buckets: HashMap<u64, Vec<&PathData>> = HashMap::default();
+
+    pathdata_set.iter().for_each(|pathdata| {
+        size_buckets
+            .entry(pathdata.size)
+            .or_default()
+            .push(pathdata);
+    });
+
+    size_buckets
+        .into_par_iter()
+        .flat_map(|(_, bucket)| {
+            bucket
+                .into_par_iter()
+                .filter_map(|pathdata| {
+                    hash_file(pathdata)
+                        .map(|digest| (pathdata.path_buf.clone(), pathdata.system_time, digest))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn to_hex(digest: &[u8; 32]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn report_as_text(report: &IntegrityReport) -> String {
+    let mut out = format!("integrity report for {:?}\n", report.live_path);
+
+    match &report.live_digest_hex {
+        Some(digest_hex) => out.push_str(&format!("  live checksum: {}\n", digest_hex)),
+        None => out.push_str("  live checksum: (no live file)\n"),
+    }
+
+    if report.live_digest_hex.is_some() && !report.live_matches_a_snapshot {
+        out.push_str("  WARNING: live content matches no snapshot version\n");
+    }
+
+    report.timeline.iter().for_each(|entry| {
+        out.push_str(&format!(
+            "  {} held in {} snapshot(s), {:?} -> {:?}\n",
+            entry.digest_hex, entry.snapshot_count, entry.first_seen, entry.last_seen
+        ));
+    });
+
+    report.suspected_bit_rot.iter().for_each(|version| {
+        out.push_str(&format!(
+            "  SUSPECTED BIT ROT: {:?} matches live size/mtime but not checksum\n",
+            version.path_buf
+        ));
+    });
+
+    out
+}
+
+pub fn report_as_json(report: &IntegrityReport) -> String {
+    let timeline_json: Vec<String> = report
+        .timeline
+        .iter()
+        .map(|entry| {
+            format!(
+                "{{\"digest\":\"{}\",\"first_seen_epoch\":{},\"last_seen_epoch\":{},\"snapshot_count\":{}}}",
+                entry.digest_hex,
+                epoch_secs(entry.first_seen),
+                epoch_secs(entry.last_seen),
+                entry.snapshot_count
+            )
+        })
+        .collect();
+
+    let bit_rot_json: Vec<String> = report
+        .suspected_bit_rot
+        .iter()
+        .map(|version| format!("\"{}\"", json_escape(&version.path_buf.to_string_lossy())))
+        .collect();
+
+    format!(
+        "{{\"live_path\":\"{}\",\"live_digest\":{},\"live_matches_a_snapshot\":{},\"timeline\":[{}],\"suspected_bit_rot\":[{}]}}",
+        json_escape(&report.live_path.to_string_lossy()),
+        match &report.live_digest_hex {
+            Some(digest_hex) => format!("\"{}\"", digest_hex),
+            None => "null".to_owned(),
+        },
+        report.live_matches_a_snapshot,
+        timeline_json.join(","),
+        bit_rot_json.join(",")
+    )
+}
+
+// paths are the only untrusted strings we embed - digests are hex and
+// everything else is a number or bool, so this is the only place that needs
+// escaping before going into the hand-rolled JSON above
+fn json_escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+
+    raw.chars().for_each(|c| match c {
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+        c => escaped.push(c),
+    });
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // a unique temp path per case, pinned to an explicit mtime so two
+    // independently-created files can collide on (size, mtime) the way a
+    // live file and a bit-rotted snapshot version would
+    fn temp_file_with_mtime(case: &str, contents: &[u8], mtime: SystemTime) -> std::path::PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("httm-verify-test-{}-{case}", std::process::id()));
+
+        let mut file = std::fs::File::create(&path).expect("create temp file");
+        file.write_all(contents).expect("write temp file");
+        drop(file);
+
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(mtime))
+            .expect("set mtime");
+
+        path
+    }
+
+    #[test]
+    fn build_report_flags_same_size_and_mtime_but_differing_digest_as_bit_rot() {
+        let shared_mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let live_path = temp_file_with_mtime("live", b"AAAAAAAAAA", shared_mtime);
+        let version_path = temp_file_with_mtime("version", b"BBBBBBBBBB", shared_mtime);
+
+        let live = PathData::from(live_path.as_path());
+        let version = PathData::from(version_path.as_path());
+
+        let report = build_report(&live, &[version]);
+
+        assert_eq!(report.suspected_bit_rot.len(), 1);
+        assert!(!report.live_matches_a_snapshot);
+
+        let _ = std::fs::remove_file(live_path);
+        let _ = std::fs::remove_file(version_path);
+    }
+
+    #[test]
+    fn build_report_does_not_flag_matching_content_as_bit_rot() {
+        let shared_mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_001);
+
+        let live_path = temp_file_with_mtime("live-match", b"identical content", shared_mtime);
+        let version_path =
+            temp_file_with_mtime("version-match", b"identical content", shared_mtime);
+
+        let live = PathData::from(live_path.as_path());
+        let version = PathData::from(version_path.as_path());
+
+        let report = build_report(&live, &[version]);
+
+        assert!(report.suspected_bit_rot.is_empty());
+        assert!(report.live_matches_a_snapshot);
+
+        let _ = std::fs::remove_file(live_path);
+        let _ = std::fs::remove_file(version_path);
+    }
+
+    #[test]
+    fn json_escape_escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(json_escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(json_escape("a\nb\tc"), "a\\nb\\tc");
+    }
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}